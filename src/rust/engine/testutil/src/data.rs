@@ -1,8 +1,10 @@
+use std::convert::TryFrom;
+
 use protobuf::Message;
 
 #[derive(Clone)]
 pub struct TestData {
-  string: String,
+  bytes: bytes::Bytes,
 }
 
 impl TestData {
@@ -30,13 +32,28 @@ impl TestData {
   }
 
   pub fn new(s: &str) -> TestData {
-    TestData {
-      string: s.to_owned(),
-    }
+    TestData::from_bytes(bytes::Bytes::from(s.to_owned()))
+  }
+
+  pub fn from_bytes(bytes: bytes::Bytes) -> TestData {
+    TestData { bytes }
+  }
+
+  // Deterministically generates `len` bytes of content, seeded by `seed`, so that tests can
+  // cheaply construct large (e.g. multi-megabyte) inputs without checking in binary fixtures.
+  pub fn of_size(len: usize, seed: u8) -> TestData {
+    let mut x: u64 = u64::from(seed);
+    let bytes: Vec<u8> = (0..len)
+      .map(|_| {
+        x = x.wrapping_mul(6364136223846793005).wrapping_add(1);
+        (x >> 56) as u8
+      })
+      .collect();
+    TestData::from_bytes(bytes::Bytes::from(bytes))
   }
 
   pub fn bytes(&self) -> bytes::Bytes {
-    bytes::Bytes::from(self.string.as_str())
+    self.bytes.clone()
   }
 
   pub fn fingerprint(&self) -> hashing::Fingerprint {
@@ -48,37 +65,78 @@ impl TestData {
   }
 
   pub fn string(&self) -> String {
-    self.string.clone()
+    String::from_utf8(self.bytes.to_vec()).expect("Not all TestData is valid UTF-8")
   }
 
   pub fn len(&self) -> usize {
-    self.string.len()
+    self.bytes.len()
   }
 }
 
+// A single leaf entry in a `TestDirectory::from_path_entries` fixture: either file content
+// (with its executable bit), or a symlink to some target path.
+#[derive(Clone)]
+pub enum DirectoryEntry {
+  File(TestData, bool),
+  Symlink(String),
+}
+
+// What a path resolved to when looked up through `TestTree::lookup`/`TestDirectory::lookup`.
+#[derive(Debug, Eq, PartialEq)]
+pub enum LookupResult {
+  File {
+    digest: hashing::Digest,
+    is_executable: bool,
+  },
+  Directory {
+    digest: hashing::Digest,
+  },
+  Symlink {
+    target: String,
+  },
+}
+
 pub struct TestDirectory {
   pub directory: bazel_protos::remote_execution::Directory,
+  // Every Directory transitively referenced from `directory`, keyed by its digest, so that a
+  // TestTree built from this TestDirectory can populate `Tree.children` per the REv2 spec.
+  children: std::collections::HashMap<hashing::Digest, bazel_protos::remote_execution::Directory>,
 }
 
 impl TestDirectory {
   pub fn empty() -> TestDirectory {
     TestDirectory {
       directory: bazel_protos::remote_execution::Directory::new(),
+      children: std::collections::HashMap::new(),
     }
   }
 
+  // Adds `subdir` as a child directory of `self` named `name`, recording its digest and
+  // merging in everything `subdir` itself transitively references.
+  fn push_subdir(&mut self, name: &str, subdir: TestDirectory) {
+    let digest = subdir.digest();
+    let mut node = bazel_protos::remote_execution::DirectoryNode::new();
+    node.set_name(name.to_owned());
+    node.set_digest((&digest).into());
+    self.directory.mut_directories().push(node);
+    self.children.extend(subdir.children);
+    self.children.insert(digest, subdir.directory);
+  }
+
+  // Every Directory transitively referenced from this TestDirectory, keyed by digest.
+  pub fn children(
+    &self,
+  ) -> std::collections::HashMap<hashing::Digest, bazel_protos::remote_execution::Directory> {
+    self.children.clone()
+  }
+
   // Directory structure:
   //
   // /falcons/
   pub fn containing_falcons_dir() -> TestDirectory {
-    let mut directory = bazel_protos::remote_execution::Directory::new();
-    directory.mut_directories().push({
-      let mut subdir = bazel_protos::remote_execution::DirectoryNode::new();
-      subdir.set_name("falcons".to_string());
-      subdir.set_digest((&TestDirectory::empty().digest()).into());
-      subdir
-    });
-    TestDirectory { directory }
+    let mut directory = TestDirectory::empty();
+    directory.push_subdir("falcons", TestDirectory::empty());
+    directory
   }
 
   // Directory structure:
@@ -86,20 +144,10 @@ impl TestDirectory {
   // birds/falcons/
   // cats/roland
   pub fn nested_dir_and_file() -> TestDirectory {
-    let mut directory = bazel_protos::remote_execution::Directory::new();
-    directory.mut_directories().push({
-      let mut subdir = bazel_protos::remote_execution::DirectoryNode::new();
-      subdir.set_name("birds".to_string());
-      subdir.set_digest((&TestDirectory::containing_falcons_dir().digest()).into());
-      subdir
-    });
-    directory.mut_directories().push({
-      let mut subdir = bazel_protos::remote_execution::DirectoryNode::new();
-      subdir.set_name("cats".to_string());
-      subdir.set_digest((&TestDirectory::containing_roland().digest()).into());
-      subdir
-    });
-    TestDirectory { directory }
+    let mut directory = TestDirectory::empty();
+    directory.push_subdir("birds", TestDirectory::containing_falcons_dir());
+    directory.push_subdir("cats", TestDirectory::containing_roland());
+    directory
   }
 
   // Directory structure:
@@ -107,14 +155,9 @@ impl TestDirectory {
   // animals/birds/falcons/
   // animals/cats/roland
   pub fn double_nested_dir_and_file() -> TestDirectory {
-    let mut directory = bazel_protos::remote_execution::Directory::new();
-    directory.mut_directories().push({
-      let mut subdir = bazel_protos::remote_execution::DirectoryNode::new();
-      subdir.set_name("animals".to_string());
-      subdir.set_digest((&TestDirectory::nested_dir_and_file().digest()).into());
-      subdir
-    });
-    TestDirectory { directory }
+    let mut directory = TestDirectory::empty();
+    directory.push_subdir("animals", TestDirectory::nested_dir_and_file());
+    directory
   }
 
   // Directory structure:
@@ -129,7 +172,10 @@ impl TestDirectory {
       file.set_is_executable(false);
       file
     });
-    TestDirectory { directory }
+    TestDirectory {
+      directory,
+      children: std::collections::HashMap::new(),
+    }
   }
 
   // Directory structure:
@@ -144,7 +190,10 @@ impl TestDirectory {
       file.set_is_executable(false);
       file
     });
-    TestDirectory { directory }
+    TestDirectory {
+      directory,
+      children: std::collections::HashMap::new(),
+    }
   }
 
   // Directory structure:
@@ -159,35 +208,28 @@ impl TestDirectory {
       file.set_is_executable(false);
       file
     });
-    TestDirectory { directory }
+    TestDirectory {
+      directory,
+      children: std::collections::HashMap::new(),
+    }
   }
 
   // Directory structure:
   //
   // /cats/roland
   pub fn nested() -> TestDirectory {
-    let mut directory = bazel_protos::remote_execution::Directory::new();
-    directory.mut_directories().push({
-      let mut subdir = bazel_protos::remote_execution::DirectoryNode::new();
-      subdir.set_name("cats".to_string());
-      subdir.set_digest((&TestDirectory::containing_roland().digest()).into());
-      subdir
-    });
-    TestDirectory { directory }
+    let mut directory = TestDirectory::empty();
+    directory.push_subdir("cats", TestDirectory::containing_roland());
+    directory
   }
 
   // Directory structure:
   //
   // /pets/cats/roland
   pub fn double_nested() -> TestDirectory {
-    let mut directory = bazel_protos::remote_execution::Directory::new();
-    directory.mut_directories().push({
-      let mut subdir = bazel_protos::remote_execution::DirectoryNode::new();
-      subdir.set_name("pets".to_string());
-      subdir.set_digest((&TestDirectory::nested().digest()).into());
-      subdir
-    });
-    TestDirectory { directory }
+    let mut directory = TestDirectory::empty();
+    directory.push_subdir("pets", TestDirectory::nested());
+    directory
   }
 
   // Directory structure:
@@ -202,7 +244,10 @@ impl TestDirectory {
       file.set_is_executable(false);
       file
     });
-    TestDirectory { directory }
+    TestDirectory {
+      directory,
+      children: std::collections::HashMap::new(),
+    }
   }
 
   // Directory structure:
@@ -217,7 +262,10 @@ impl TestDirectory {
       file.set_is_executable(false);
       file
     });
-    TestDirectory { directory }
+    TestDirectory {
+      directory,
+      children: std::collections::HashMap::new(),
+    }
   }
 
   // Directory structure:
@@ -240,7 +288,10 @@ impl TestDirectory {
       file.set_is_executable(false);
       file
     });
-    TestDirectory { directory }
+    TestDirectory {
+      directory,
+      children: std::collections::HashMap::new(),
+    }
   }
 
   // Directory structure:
@@ -248,21 +299,9 @@ impl TestDirectory {
   // /cats/roland
   // /treats
   pub fn recursive() -> TestDirectory {
-    let mut directory = bazel_protos::remote_execution::Directory::new();
-    directory.mut_directories().push({
-      let mut subdir = bazel_protos::remote_execution::DirectoryNode::new();
-      subdir.set_name("cats".to_string());
-      subdir.set_digest((&TestDirectory::containing_roland().digest()).into());
-      subdir
-    });
-    directory.mut_files().push({
-      let mut file = bazel_protos::remote_execution::FileNode::new();
-      file.set_name("treats".to_string());
-      file.set_digest((&TestData::catnip().digest()).into());
-      file.set_is_executable(false);
-      file
-    });
-    TestDirectory { directory }
+    let mut directory = TestDirectory::containing_treats();
+    directory.push_subdir("cats", TestDirectory::containing_roland());
+    directory
   }
 
   // Directory structure:
@@ -285,7 +324,138 @@ impl TestDirectory {
       file.set_is_executable(false);
       file
     });
-    TestDirectory { directory }
+    TestDirectory {
+      directory,
+      children: std::collections::HashMap::new(),
+    }
+  }
+
+  // Directory structure:
+  //
+  // /<name> -> <target>
+  pub fn containing_symlink(name: &str, target: &str) -> TestDirectory {
+    let mut directory = bazel_protos::remote_execution::Directory::new();
+    directory.mut_symlinks().push({
+      let mut symlink = bazel_protos::remote_execution::SymlinkNode::new();
+      symlink.set_name(name.to_owned());
+      symlink.set_target(target.to_owned());
+      symlink
+    });
+    TestDirectory {
+      directory,
+      children: std::collections::HashMap::new(),
+    }
+  }
+
+  // Builds a TestDirectory from a flat list of `(path, data, is_executable)` entries, e.g.
+  // `&[("birds/falcons/egg", TestData::roland(), false)]`, splitting each path on `/` and
+  // recursing to assemble the subdirectories that path implies.
+  pub fn from_paths(entries: &[(&str, TestData, bool)]) -> TestDirectory {
+    let owned_entries: Vec<(String, DirectoryEntry)> = entries
+      .iter()
+      .map(|(path, data, is_executable)| {
+        (
+          path.to_string(),
+          DirectoryEntry::File(data.clone(), *is_executable),
+        )
+      })
+      .collect();
+    TestDirectory::from_owned_entries(owned_entries)
+  }
+
+  // As `from_paths`, but with a `DirectoryEntry` per path so that fixtures can mix in
+  // symlinks (and, in future, other REv2 node kinds) alongside plain files.
+  pub fn from_path_entries(entries: &[(&str, DirectoryEntry)]) -> TestDirectory {
+    let owned_entries: Vec<(String, DirectoryEntry)> = entries
+      .iter()
+      .map(|(path, entry)| (path.to_string(), entry.clone()))
+      .collect();
+    TestDirectory::from_owned_entries(owned_entries)
+  }
+
+  fn from_owned_entries(entries: Vec<(String, DirectoryEntry)>) -> TestDirectory {
+    let mut directory = TestDirectory::empty();
+    let mut immediate: Vec<(String, DirectoryEntry)> = Vec::new();
+    let mut subdirs: std::collections::HashMap<String, Vec<(String, DirectoryEntry)>> =
+      std::collections::HashMap::new();
+
+    for (path, entry) in entries {
+      match path.split_once('/') {
+        Some((head, rest)) => subdirs
+          .entry(head.to_string())
+          .or_insert_with(Vec::new)
+          .push((rest.to_string(), entry)),
+        None => immediate.push((path, entry)),
+      }
+    }
+
+    for (name, entry) in immediate {
+      match entry {
+        DirectoryEntry::File(data, is_executable) => {
+          let mut file = bazel_protos::remote_execution::FileNode::new();
+          file.set_name(name);
+          file.set_digest((&data.digest()).into());
+          file.set_is_executable(is_executable);
+          directory.directory.mut_files().push(file);
+        }
+        DirectoryEntry::Symlink(target) => {
+          let mut symlink = bazel_protos::remote_execution::SymlinkNode::new();
+          symlink.set_name(name);
+          symlink.set_target(target);
+          directory.directory.mut_symlinks().push(symlink);
+        }
+      }
+    }
+
+    for (name, sub_entries) in subdirs {
+      let subdirectory = TestDirectory::from_owned_entries(sub_entries);
+      directory.push_subdir(&name, subdirectory);
+    }
+
+    directory
+      .directory
+      .mut_files()
+      .sort_by(|a, b| a.get_name().cmp(b.get_name()));
+    directory
+      .directory
+      .mut_directories()
+      .sort_by(|a, b| a.get_name().cmp(b.get_name()));
+    directory
+      .directory
+      .mut_symlinks()
+      .sort_by(|a, b| a.get_name().cmp(b.get_name()));
+
+    let mut seen = std::collections::HashSet::new();
+    for name in directory
+      .directory
+      .get_files()
+      .iter()
+      .map(|f| f.get_name())
+      .chain(
+        directory
+          .directory
+          .get_directories()
+          .iter()
+          .map(|d| d.get_name()),
+      )
+      .chain(
+        directory
+          .directory
+          .get_symlinks()
+          .iter()
+          .map(|s| s.get_name()),
+      )
+    {
+      if !seen.insert(name) {
+        panic!(
+          "TestDirectory::from_paths saw the name `{}` more than once, or used as both a file, \
+           directory, or symlink",
+          name
+        );
+      }
+    }
+
+    directory
   }
 
   pub fn directory(&self) -> bazel_protos::remote_execution::Directory {
@@ -308,6 +478,62 @@ impl TestDirectory {
   pub fn digest(&self) -> hashing::Digest {
     hashing::Digest::of_bytes(&self.bytes())
   }
+
+  // Resolves `path` (e.g. `"animals/cats/roland"`) against this directory, descending through
+  // `children` to follow subdirectories. Empty, `.`, and trailing-slash components are ignored;
+  // a component that matches nothing yields `None` rather than panicking.
+  pub fn lookup(&self, path: &str) -> Option<LookupResult> {
+    TestDirectory::lookup_in(&self.directory, path, &self.children)
+  }
+
+  fn lookup_in(
+    directory: &bazel_protos::remote_execution::Directory,
+    path: &str,
+    children: &std::collections::HashMap<
+      hashing::Digest,
+      bazel_protos::remote_execution::Directory,
+    >,
+  ) -> Option<LookupResult> {
+    let mut components = path.split('/').filter(|c| !c.is_empty() && *c != ".");
+    let name = components.next()?;
+    let rest: Vec<&str> = components.collect();
+
+    if rest.is_empty() {
+      if let Some(file) = directory.get_files().iter().find(|f| f.get_name() == name) {
+        return Some(LookupResult::File {
+          digest: hashing::Digest::try_from(file.get_digest()).ok()?,
+          is_executable: file.get_is_executable(),
+        });
+      }
+      if let Some(dir_node) = directory
+        .get_directories()
+        .iter()
+        .find(|d| d.get_name() == name)
+      {
+        return Some(LookupResult::Directory {
+          digest: hashing::Digest::try_from(dir_node.get_digest()).ok()?,
+        });
+      }
+      if let Some(symlink) = directory
+        .get_symlinks()
+        .iter()
+        .find(|s| s.get_name() == name)
+      {
+        return Some(LookupResult::Symlink {
+          target: symlink.get_target().to_owned(),
+        });
+      }
+      return None;
+    }
+
+    let dir_node = directory
+      .get_directories()
+      .iter()
+      .find(|d| d.get_name() == name)?;
+    let digest = hashing::Digest::try_from(dir_node.get_digest()).ok()?;
+    let subdirectory = children.get(&digest)?;
+    TestDirectory::lookup_in(subdirectory, &rest.join("/"), children)
+  }
 }
 
 pub struct TestTree {
@@ -336,11 +562,26 @@ impl TestTree {
   pub fn digest(&self) -> hashing::Digest {
     hashing::Digest::of_bytes(&self.bytes())
   }
+
+  // As `TestDirectory::lookup`, but resolving subdirectories out of this Tree's `children`.
+  pub fn lookup(&self, path: &str) -> Option<LookupResult> {
+    let mut children = std::collections::HashMap::new();
+    for child in self.tree.get_children() {
+      let digest = hashing::Digest::of_bytes(&bytes::Bytes::from(
+        child.write_to_bytes().expect("Error serializing proto"),
+      ));
+      children.insert(digest, child.clone());
+    }
+    TestDirectory::lookup_in(self.tree.get_root(), path, &children)
+  }
 }
 
 impl From<TestDirectory> for TestTree {
   fn from(dir: TestDirectory) -> Self {
     let mut tree = bazel_protos::remote_execution::Tree::new();
+    for child in dir.children.into_values() {
+      tree.mut_children().push(child);
+    }
     tree.set_root(dir.directory);
     TestTree { tree }
   }